@@ -1,10 +1,37 @@
-/// Cargo.toml の [dependencies] セクションなどに
-/// itertools = "0.10"   を追加してください。
 use itertools::Itertools;
-use chrono::{Utc, Local, DateTime, Date};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
 /// 2次元座標を表す型エイリアス
 type Point = (i32, i32);
 
+/// ユークリッドの互除法による最大公約数(常に非負を返す)
+fn gcd_i128(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd_i128(b, a % b)
+    }
+}
+
+/// `gcd_i128` の `i64` 版
+fn gcd_i64(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd_i64(b, a % b)
+    }
+}
+
+/// 3x3 行列の厳密整数行列式
+fn det3(m: [[i128; 3]; 3]) -> i128 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
 /// 3点が厳密に同一直線上にあるかどうか（面積が0かどうか）
 /// 面積 2 倍の値(外積)が 0 なら collinear
 fn area2(p1: &Point, p2: &Point, p3: &Point) -> i32 {
@@ -31,69 +58,93 @@ fn four_points_are_collinear(p1: &Point, p2: &Point, p3: &Point, p4: &Point) ->
 /// | x_k^2 + y_k^2   x_k   y_k   1 |
 /// | x_l^2 + y_l^2   x_l   y_l   1 |
 ///
-/// 絶対値がごく小さい(浮動小数で 0 相当)なら同一円周上。
-/// ただし、4点が厳密に同一直線上の場合は「同一円周上」とみなさない。
+/// 座標はすべて `i32` なので、この行列式は厳密な整数値になる。
+/// `f64` + イプシロンで判定すると、桁が大きいグリッドで誤差によって
+/// 偽陽性・偽陰性が起こり得るため、`i128` による厳密整数演算で
+/// `det == 0` を直接判定する。
+/// なお、4点が厳密に同一直線上の場合は「同一円周上」とみなさない
+/// （同一直線上の4点を通る円は存在しない）。
 fn four_points_are_concyclic(p1: &Point, p2: &Point, p3: &Point, p4: &Point) -> bool {
-    // まず4点が同一直線上なら false を返す
+    // まず4点が同一直線上なら、円周上とはみなさない
     if four_points_are_collinear(p1, p2, p3, p4) {
-        return true;
+        return false;
     }
 
-    // 4x4行列を作成し、行列式が 0 かどうか調べる
-    fn det4(m: [[f64; 4]; 4]) -> f64 {
-        // 行列式を直接計算(展開法 or その他の方法)
-        // ここでは展開法(サラスの公式)をなるべく避け、汎用的に書いても良いですが
-        // コード量が多くなるため、ラプラス展開など適当な実装を簡易的に行います。
-        //
-        // Rust で n=4 の行列式を愚直に書くなら、子行列の 3x3 の行列式と符号を使った
-        // ラプラス展開が分かりやすいです。
-        //
-        // ここでは簡単のために余因子展開をベタ書きします。
-
-        let mut d: f64 = 0.0;
+    // 4x4行列を作成し、行列式が厳密に 0 かどうか調べる
+    fn det4(m: [[i128; 4]; 4]) -> i128 {
+        // 子行列の 3x3 の行列式と符号を使ったラプラス展開
+        let mut d: i128 = 0;
         for i in 0..4 {
             // 余因子 C(i,0) = (-1)^(i+0) * det(M_i0) (M_i0 は行0列iを除いた3x3小行列)
-            let mut sub = [[0.0; 3]; 3];
+            let mut sub = [[0i128; 3]; 3];
             for (sub_row, row) in (0..4).filter(|&r| r != 0).enumerate() {
                 let mut sub_col_idx = 0;
-                for col in 0..4 {
+                for (col, &value) in m[row].iter().enumerate() {
                     if col == i {
                         continue;
                     }
-                    sub[sub_row][sub_col_idx] = m[row][col];
+                    sub[sub_row][sub_col_idx] = value;
                     sub_col_idx += 1;
                 }
             }
-            let sign = if (i + 0) % 2 == 0 { 1.0 } else { -1.0 };
+            let sign: i128 = if i % 2 == 0 { 1 } else { -1 };
             d += sign * m[0][i] * det3(sub);
         }
         d
     }
 
-    fn det3(m: [[f64; 3]; 3]) -> f64 {
-        // 3x3 行列式
-        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
-            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
-            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
-    }
-
     let (x1, y1) = *p1;
     let (x2, y2) = *p2;
     let (x3, y3) = *p3;
     let (x4, y4) = *p4;
 
+    let sq = |x: i32, y: i32| -> i128 { (x as i128) * (x as i128) + (y as i128) * (y as i128) };
+
     let mat = [
-        [(x1 * x1 + y1 * y1) as f64, x1 as f64, y1 as f64, 1.0],
-        [(x2 * x2 + y2 * y2) as f64, x2 as f64, y2 as f64, 1.0],
-        [(x3 * x3 + y3 * y3) as f64, x3 as f64, y3 as f64, 1.0],
-        [(x4 * x4 + y4 * y4) as f64, x4 as f64, y4 as f64, 1.0],
+        [sq(x1, y1), x1 as i128, y1 as i128, 1],
+        [sq(x2, y2), x2 as i128, y2 as i128, 1],
+        [sq(x3, y3), x3 as i128, y3 as i128, 1],
+        [sq(x4, y4), x4 as i128, y4 as i128, 1],
     ];
 
-    let det_value = det4(mat);
+    det4(mat) == 0
+}
+
+#[cfg(test)]
+mod concyclic_tests {
+    use super::*;
+
+    #[test]
+    fn four_points_on_unit_circle_are_concyclic() {
+        assert!(four_points_are_concyclic(
+            &(1, 0),
+            &(0, 1),
+            &(-1, 0),
+            &(0, -1)
+        ));
+    }
 
-    // 浮動小数点誤差を考慮し、絶対値が非常に小さければ 0 とみなす
-    const EPS: f64 = 1.0e-12;
-    det_value.abs() < EPS
+    #[test]
+    fn four_points_off_circle_are_not_concyclic() {
+        assert!(!four_points_are_concyclic(
+            &(0, 0),
+            &(2, 0),
+            &(0, 2),
+            &(3, 3)
+        ));
+    }
+
+    #[test]
+    fn four_collinear_points_are_not_concyclic() {
+        // 同一直線上の4点を通る円は存在しないので、false でなければならない
+        // (ドキュメントとコードが矛盾していた過去のバグの回帰テスト)
+        assert!(!four_points_are_concyclic(
+            &(0, 0),
+            &(1, 0),
+            &(2, 0),
+            &(3, 0)
+        ));
+    }
 }
 
 /// 部分集合 subset 内に 4 点が同一円周上となる組合せが一つでもあれば true
@@ -111,40 +162,958 @@ fn has_any_4_concyclic(subset: &[Point]) -> bool {
     false
 }
 
-fn main() {
-    let hen = 6;
-    let mut all_points: Vec<Point> = (0..=hen)
-        .flat_map(|x| (0..=hen).map(move |y| (x, y)))
+/// 3点が乗る円を、係数 (D, E, F) の厳密な有理数として
+/// `x^2 + y^2 + D*x + E*y + F = 0` の形で表したときの正規化キー。
+///
+/// `D = Dn/den`, `E = En/den`, `F = Fn/den` を、4つ組 `(Dn, En, Fn, den)` を
+/// それらの最大公約数で割り、`den` が正になるよう符号を揃えることで
+/// 同じ円であれば必ず同じキーになるようにする。3点が共線の場合、
+/// この円は存在しない（半径が無限大の直線に退化する）ので `None` を返す。
+type CircleKey = (i128, i128, i128, i128);
+
+fn circle_key(p1: &Point, p2: &Point, p3: &Point) -> Option<CircleKey> {
+    if area2(p1, p2, p3) == 0 {
+        return None;
+    }
+
+    let (x1, y1) = *p1;
+    let (x2, y2) = *p2;
+    let (x3, y3) = *p3;
+    let a = |x: i32, y: i32| -> i128 { (x as i128) * (x as i128) + (y as i128) * (y as i128) };
+    let (a1, a2, a3) = (a(x1, y1), a(x2, y2), a(x3, y3));
+    let (x1, y1, x2, y2, x3, y3) = (
+        x1 as i128, y1 as i128, x2 as i128, y2 as i128, x3 as i128, y3 as i128,
+    );
+
+    // [x_i y_i 1][D]   [-a_i]
+    // [       ] [E] = [    ]
+    // [       ] [F]   [    ]
+    let den = det3([[x1, y1, 1], [x2, y2, 1], [x3, y3, 1]]);
+    let dn = det3([[-a1, y1, 1], [-a2, y2, 1], [-a3, y3, 1]]);
+    let en = det3([[x1, -a1, 1], [x2, -a2, 1], [x3, -a3, 1]]);
+    let fn_ = det3([[x1, y1, -a1], [x2, y2, -a2], [x3, y3, -a3]]);
+
+    let g = gcd_i128(gcd_i128(dn, en), gcd_i128(fn_, den)).max(1);
+    let (dn, en, fn_, den) = (dn / g, en / g, fn_ / g, den / g);
+    if den < 0 {
+        Some((-dn, -en, -fn_, -den))
+    } else {
+        Some((dn, en, fn_, den))
+    }
+}
+
+/// グリッド上の各円(共線でない3点の組から求まる円)について、
+/// その円を通る点の添字一覧を求める。キーは `circle_key` で正規化済みなので、
+/// 異なる3点組から見つかった円でも同じ円であれば1つにまとまる。
+///
+/// 3点の組合せから作っている時点でメンバーは必ず3点以上になるが、
+/// 同一円周上4点という制約に関わるのは4点以上が乗る円だけなので、
+/// 戻り値にはメンバーが4点以上の円だけを残す(ちょうど3点の円は含まない)。
+fn build_circle_groups(all_points: &[Point]) -> Vec<(CircleKey, Vec<usize>)> {
+    let mut groups: HashMap<CircleKey, Vec<usize>> = HashMap::new();
+    for comb in (0..all_points.len()).combinations(3) {
+        let (i, j, k) = (comb[0], comb[1], comb[2]);
+        if let Some(key) = circle_key(&all_points[i], &all_points[j], &all_points[k]) {
+            let members = groups.entry(key).or_default();
+            for &idx in &[i, j, k] {
+                if !members.contains(&idx) {
+                    members.push(idx);
+                }
+            }
+        }
+    }
+    // 4点に満たない円は、同一円周上4点という制約には決して関わらないので除く
+    groups.into_iter().filter(|(_, m)| m.len() >= 4).collect()
+}
+
+/// 2点を通る直線を `a*x + b*y + c = 0` の形で表したときの正規化キー。
+/// `(a, b, c)` を最大公約数で割り、先頭の非零成分が正になるよう符号を揃える。
+type LineKey = (i64, i64, i64);
+
+fn line_key(p1: &Point, p2: &Point) -> LineKey {
+    let (x1, y1) = *p1;
+    let (x2, y2) = *p2;
+    let dx = (x2 - x1) as i64;
+    let dy = (y2 - y1) as i64;
+    // dy*(x - x1) - dx*(y - y1) = 0  =>  dy*x - dx*y + (dx*y1 - dy*x1) = 0
+    let (a, b, c) = (dy, -dx, dx * y1 as i64 - dy * x1 as i64);
+
+    let g = gcd_i64(gcd_i64(a, b), c).max(1);
+    let (a, b, c) = (a / g, b / g, c / g);
+    if a < 0 || (a == 0 && b < 0) {
+        (-a, -b, -c)
+    } else {
+        (a, b, c)
+    }
+}
+
+/// グリッド上の各直線について、その直線上の点の添字一覧を求める。
+/// `build_circle_groups` と同じ考え方で、4点未満しか乗らない直線は除く。
+fn build_line_groups(all_points: &[Point]) -> Vec<(LineKey, Vec<usize>)> {
+    let mut groups: HashMap<LineKey, Vec<usize>> = HashMap::new();
+    for comb in (0..all_points.len()).combinations(2) {
+        let (i, j) = (comb[0], comb[1]);
+        let key = line_key(&all_points[i], &all_points[j]);
+        let members = groups.entry(key).or_default();
+        for &idx in &[i, j] {
+            if !members.contains(&idx) {
+                members.push(idx);
+            }
+        }
+    }
+    groups.into_iter().filter(|(_, m)| m.len() >= 4).collect()
+}
+
+#[cfg(test)]
+mod key_tests {
+    use super::*;
+
+    #[test]
+    fn circle_key_is_same_for_any_three_points_on_the_same_circle() {
+        // (1,0),(0,1),(-1,0),(0,-1) はすべて単位円周上にあるので、
+        // どの3点の組から求めても同じキーになるはずである
+        let k1 = circle_key(&(1, 0), &(0, 1), &(-1, 0));
+        let k2 = circle_key(&(0, 1), &(-1, 0), &(0, -1));
+        assert!(k1.is_some());
+        assert_eq!(k1, k2);
+    }
+
+    #[test]
+    fn circle_key_is_none_for_collinear_points() {
+        assert_eq!(circle_key(&(0, 0), &(1, 0), &(2, 0)), None);
+    }
+
+    #[test]
+    fn line_key_is_same_regardless_of_point_order_or_which_two_points_are_used() {
+        let k1 = line_key(&(0, 0), &(2, 0));
+        let k2 = line_key(&(2, 0), &(4, 0));
+        let k3 = line_key(&(4, 0), &(0, 0));
+        assert_eq!(k1, k2);
+        assert_eq!(k1, k3);
+    }
+
+    #[test]
+    fn line_key_differs_for_distinct_lines() {
+        assert_ne!(line_key(&(0, 0), &(2, 0)), line_key(&(0, 0), &(0, 2)));
+    }
+}
+
+/// 円に乗る4点以上のグループを `Vec<Vec<Point>>` として返す。
+/// 「同一円周上の点を高々3個しか選べない」という制約の、点集合そのものによる表現。
+pub fn concyclic_groups(all_points: &[Point]) -> Vec<Vec<Point>> {
+    build_circle_groups(all_points)
+        .into_iter()
+        .map(|(_, members)| members.into_iter().map(|i| all_points[i]).collect())
+        .collect()
+}
+
+/// 直線上に乗る4点以上のグループを `Vec<Vec<Point>>` として返す。
+pub fn collinear_groups(all_points: &[Point]) -> Vec<Vec<Point>> {
+    build_line_groups(all_points)
+        .into_iter()
+        .map(|(_, members)| members.into_iter().map(|i| all_points[i]).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod group_view_tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // (0,0),(1,0),(2,0),(3,0) は x軸上に4点並ぶ直線を、
+    // (0,0),(0,2),(2,0),(2,2) は正方形の頂点として1つの円を、それぞれ作る
+    const POINTS: [Point; 6] = [(0, 0), (1, 0), (2, 0), (3, 0), (0, 2), (2, 2)];
+
+    #[test]
+    fn concyclic_groups_finds_the_single_circle_of_four() {
+        let groups = concyclic_groups(&POINTS);
+        assert_eq!(groups.len(), 1);
+        let members: HashSet<Point> = groups[0].iter().copied().collect();
+        let expected: HashSet<Point> = [(0, 0), (0, 2), (2, 0), (2, 2)].into_iter().collect();
+        assert_eq!(members, expected);
+    }
+
+    #[test]
+    fn collinear_groups_finds_the_single_line_of_four() {
+        let groups = collinear_groups(&POINTS);
+        assert_eq!(groups.len(), 1);
+        let members: HashSet<Point> = groups[0].iter().copied().collect();
+        let expected: HashSet<Point> = [(0, 0), (1, 0), (2, 0), (3, 0)].into_iter().collect();
+        assert_eq!(members, expected);
+    }
+}
+
+/// 既約分数 `num/den` を `den > 0` かつ互いに素になるよう正規化して返す。
+fn reduce_fraction(num: i128, den: i128) -> (i128, i128) {
+    let g = gcd_i128(num, den).max(1);
+    if den < 0 {
+        (-num / g, -den / g)
+    } else {
+        (num / g, den / g)
+    }
+}
+
+fn fraction_to_string(num: i128, den: i128) -> String {
+    let (num, den) = reduce_fraction(num, den);
+    if den == 1 {
+        format!("{}", num)
+    } else {
+        format!("{}/{}", num, den)
+    }
+}
+
+/// 「同一円周上には高々3点まで」という制約を、円・直線それぞれの
+/// グループとして一覧表示する診断用の関数。各円については
+/// `x^2+y^2+Dx+Ey+F=0` の係数から中心と半径^2を厳密な有理数として求める。
+pub fn print_constraint_groups(all_points: &[Point]) {
+    let circles = build_circle_groups(all_points);
+    println!("同一円周上4点以上のグループ: {} 件", circles.len());
+    for (key, members) in &circles {
+        let (dn, en, fn_, den) = *key;
+        let center_x = fraction_to_string(-dn, 2 * den);
+        let center_y = fraction_to_string(-en, 2 * den);
+        let radius2_num = dn * dn + en * en - 4 * fn_ * den;
+        let radius2_den = 4 * den * den;
+        let radius2 = fraction_to_string(radius2_num, radius2_den);
+        let points: Vec<Point> = members.iter().map(|&i| all_points[i]).collect();
+        println!(
+            "  中心 = ({}, {}), 半径^2 = {}, 点 = {:?}",
+            center_x, center_y, radius2, points
+        );
+    }
+
+    let lines = build_line_groups(all_points);
+    println!("同一直線上4点以上のグループ: {} 件", lines.len());
+    for (key, members) in &lines {
+        let (a, b, c) = *key;
+        let points: Vec<Point> = members.iter().map(|&i| all_points[i]).collect();
+        println!("  {}x + {}y + {} = 0, 点 = {:?}", a, b, c, points);
+    }
+
+    let mut size_counts: HashMap<usize, usize> = HashMap::new();
+    let group_sizes = circles
+        .iter()
+        .map(|(_, m)| m.len())
+        .chain(lines.iter().map(|(_, m)| m.len()));
+    for size in group_sizes {
+        *size_counts.entry(size).or_insert(0) += 1;
+    }
+    let mut sizes: Vec<&usize> = size_counts.keys().collect();
+    sizes.sort();
+    for k in sizes {
+        println!("  ちょうど{}点を含むグループの数: {}", k, size_counts[k]);
+    }
+}
+
+/// 各点が、どの円グループ(`circles` の添字)に乗っているかの一覧
+fn point_to_circles(n_points: usize, circles: &[(CircleKey, Vec<usize>)]) -> Vec<Vec<usize>> {
+    let mut point_circles = vec![Vec::new(); n_points];
+    for (circle_id, (_, members)) in circles.iter().enumerate() {
+        for &p in members {
+            point_circles[p].push(circle_id);
+        }
+    }
+    point_circles
+}
+
+/// 同一円周上4点を作らないという不変条件を保ちながら、
+/// グリッドの点を深さ優先で選んでいく branch-and-bound 探索。
+///
+/// `counts[circle_id]` は、現在選択中の部分集合がその円に何点乗せているかを
+/// 保持する。ある点を追加しようとしたとき、その点が乗っている円のどれかが
+/// 既に3点を抱えていれば(追加すると4点になり違反するので)その枝は切る。
+/// 上界刈り込みとして、「現在の選択数 + 残り候補点数 <= best」なら
+/// これ以上 best を更新できないので探索を打ち切る。
+/// `node_count` はスループット計測用に訪れたノード数を積算する。
+fn search_max_no_4_concyclic(
+    idx: usize,
+    all_points: &[Point],
+    point_circles: &[Vec<usize>],
+    counts: &mut [u8],
+    chosen: &mut Vec<usize>,
+    best: &mut Vec<usize>,
+    node_count: &mut u64,
+) {
+    *node_count += 1;
+    if chosen.len() + (all_points.len() - idx) <= best.len() {
+        return;
+    }
+    if idx == all_points.len() {
+        return;
+    }
+
+    // 点 idx を選ぶ枝: 乗っている円のどれかが既に3点ならこの点は選べない
+    let can_take = point_circles[idx].iter().all(|&cid| counts[cid] < 3);
+    if can_take {
+        for &cid in &point_circles[idx] {
+            counts[cid] += 1;
+        }
+        chosen.push(idx);
+        if chosen.len() > best.len() {
+            *best = chosen.clone();
+        }
+        search_max_no_4_concyclic(
+            idx + 1,
+            all_points,
+            point_circles,
+            counts,
+            chosen,
+            best,
+            node_count,
+        );
+        chosen.pop();
+        for &cid in &point_circles[idx] {
+            counts[cid] -= 1;
+        }
+    }
+
+    // 点 idx を選ばない枝
+    search_max_no_4_concyclic(
+        idx + 1,
+        all_points,
+        point_circles,
+        counts,
+        chosen,
+        best,
+        node_count,
+    );
+}
+
+#[cfg(test)]
+mod search_max_no_4_concyclic_tests {
+    use super::*;
+
+    /// 全部分集合をしらみつぶしに試して最大の「同一円周上4点なし」部分集合の
+    /// サイズを求める、branch-and-bound とは独立な基準実装。
+    fn brute_force_max_no_4_concyclic(all_points: &[Point]) -> usize {
+        let n = all_points.len();
+        for size in (0..=n).rev() {
+            let found = (0..n).combinations(size).any(|idxs| {
+                let subset: Vec<Point> = idxs.iter().map(|&i| all_points[i]).collect();
+                !has_any_4_concyclic(&subset)
+            });
+            if found {
+                return size;
+            }
+        }
+        0
+    }
+
+    #[test]
+    fn matches_brute_force_on_3x3_grid() {
+        let all_points: Vec<Point> = (0..3).flat_map(|x| (0..3).map(move |y| (x, y))).collect();
+        let circles = build_circle_groups(&all_points);
+        let point_circles = point_to_circles(all_points.len(), &circles);
+
+        let mut counts = vec![0u8; circles.len()];
+        let mut chosen = Vec::new();
+        let mut best = Vec::new();
+        let mut node_count = 0u64;
+        search_max_no_4_concyclic(
+            0,
+            &all_points,
+            &point_circles,
+            &mut counts,
+            &mut chosen,
+            &mut best,
+            &mut node_count,
+        );
+
+        let best_points: Vec<Point> = best.iter().map(|&i| all_points[i]).collect();
+        assert!(!has_any_4_concyclic(&best_points));
+        assert_eq!(best.len(), brute_force_max_no_4_concyclic(&all_points));
+    }
+}
+
+/// 候補点集合 `all_points` の指紋。チェックポイントファイルに埋め込み、
+/// 読み込み時に現在の実行と照合することで、別のグリッド・別の領域で
+/// 書かれた(あるいは単に古い)チェックポイントを誤って再利用しないようにする。
+fn fingerprint_points(all_points: &[Point]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    all_points.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// 探索の進捗を記録するチェックポイント。
+///
+/// `completed_starts` は「選択した点の中で最小の添字がこの値であるような
+/// 部分空間」の探索が完了済みであることを示す。`best` はこれまでに
+/// 見つかった最大の部分集合(点そのものの列として保存する。グリッドの
+/// 定義を変えない限り、点と添字の対応は実行ごとに変わらないため)。
+struct SearchCheckpoint {
+    completed_starts: std::collections::HashSet<usize>,
+    best: Vec<Point>,
+}
+
+fn checkpoint_path() -> &'static str {
+    "kyoen_search.checkpoint"
+}
+
+/// `path` からチェックポイントを読み込む。ファイルに記録された指紋が
+/// `expected_fingerprint` と一致しない場合(別の点集合で書かれたもの、
+/// 壊れたファイル、指紋を持たない古い形式のファイルなど)は、中身を
+/// 一切信用せず空のチェックポイントとして扱う。
+fn load_checkpoint(path: &str, expected_fingerprint: u64) -> SearchCheckpoint {
+    let empty = SearchCheckpoint {
+        completed_starts: std::collections::HashSet::new(),
+        best: Vec::new(),
+    };
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return empty,
+    };
+
+    let mut fingerprint: Option<u64> = None;
+    let mut completed_starts = std::collections::HashSet::new();
+    let mut best = Vec::new();
+    for line in content.lines() {
+        if let Some(rest) = line.strip_prefix("fingerprint:") {
+            fingerprint = rest.trim().parse::<u64>().ok();
+        } else if let Some(rest) = line.strip_prefix("done:") {
+            if let Ok(start) = rest.trim().parse::<usize>() {
+                completed_starts.insert(start);
+            }
+        } else if let Some(rest) = line.strip_prefix("best:") {
+            best = rest
+                .trim()
+                .split(';')
+                .filter(|s| !s.is_empty())
+                .filter_map(|pair| {
+                    let mut parts = pair.split(',');
+                    let x = parts.next()?.parse::<i32>().ok()?;
+                    let y = parts.next()?.parse::<i32>().ok()?;
+                    Some((x, y))
+                })
+                .collect();
+        }
+    }
+
+    if fingerprint != Some(expected_fingerprint) {
+        if fingerprint.is_some() {
+            eprintln!(
+                "警告: チェックポイント {} の点集合指紋が現在の実行と一致しないため、\
+                 破棄して最初から探索します",
+                path
+            );
+        }
+        return empty;
+    }
+
+    SearchCheckpoint {
+        completed_starts,
+        best,
+    }
+}
+
+fn save_checkpoint(
+    path: &str,
+    fingerprint: u64,
+    completed_starts: &std::collections::HashSet<usize>,
+    best: &[Point],
+) {
+    let best_str = best
+        .iter()
+        .map(|(x, y)| format!("{},{}", x, y))
+        .collect::<Vec<_>>()
+        .join(";");
+    let mut sorted_starts: Vec<&usize> = completed_starts.iter().collect();
+    sorted_starts.sort();
+    let mut content = format!("fingerprint:{}\nbest:{}\n", fingerprint, best_str);
+    for start in sorted_starts {
+        content.push_str(&format!("done:{}\n", start));
+    }
+    // チェックポイントの保存は最善努力でよい(失敗しても探索自体は続行する)
+    let _ = std::fs::write(path, content);
+}
+
+/// `search_max_no_4_concyclic` を、選んだ点の中で最小の添字を `start` に
+/// 固定した部分空間に限定して実行する。つまり `start` 未満の点はすべて
+/// 除外し、`start` は必ず選んだ上で `start + 1` 以降を探索する。
+/// これにより探索空間全体を `start` の値ごとに分割でき、各分割は
+/// 互いに独立なので rayon でそのまま並列化できる。
+fn search_max_no_4_concyclic_from_start(
+    start: usize,
+    all_points: &[Point],
+    point_circles: &[Vec<usize>],
+    circle_count: usize,
+) -> (Vec<usize>, u64) {
+    let mut counts = vec![0u8; circle_count];
+    for &cid in &point_circles[start] {
+        counts[cid] += 1;
+    }
+    let mut chosen = vec![start];
+    let mut best = vec![start];
+    let mut node_count = 0u64;
+    search_max_no_4_concyclic(
+        start + 1,
+        all_points,
+        point_circles,
+        &mut counts,
+        &mut chosen,
+        &mut best,
+        &mut node_count,
+    );
+    (best, node_count)
+}
+
+/// 探索空間全体を最初に選ぶ点(`start`)ごとに分割し、rayon の並列イテレータで
+/// 各分割を別スレッドで探索する。分割ごとに探索が終わるたびにチェックポイント
+/// ファイル(`checkpoint_path`)へ反映するので、長時間実行が中断されても
+/// 次回起動時に完了済みの分割を読み飛ばして再開できる。チェックポイントには
+/// `all_points` の指紋を埋め込み、読み込み時に今回の実行と一致するものしか
+/// 使わない(`load_checkpoint` 参照)ので、別のグリッド・別の領域で
+/// 書かれたチェックポイントを誤って流用することはない。
+/// 分割が1つ完了するたびに、完了数・累計ノード数・経過時間を標準出力へ
+/// 進捗として表示する(長時間実行でも、進んでいるのか止まっているのかが
+/// わかるようにするため)。戻り値は最良の部分集合と、全スレッド合計の探索ノード数。
+fn search_max_no_4_concyclic_parallel(
+    all_points: &[Point],
+    point_circles: &[Vec<usize>],
+    circle_count: usize,
+    checkpoint_path: &str,
+) -> (Vec<usize>, u64) {
+    let fingerprint = fingerprint_points(all_points);
+    let checkpoint = load_checkpoint(checkpoint_path, fingerprint);
+    let best_indices: Vec<usize> = checkpoint
+        .best
+        .iter()
+        .filter_map(|p| all_points.iter().position(|q| q == p))
         .collect();
-    println!("大きさは{}x{}", hen + 1, hen + 1);
-    for n in 13..=25 {
-        // 25点から n 点を選ぶ
-        // itertools の combinations を使う
-        let mut found_good_subset = false;
-        println!("{}", all_points.len());
-        let mut t:i64 = 0;
-        for subset in all_points.iter().combinations(n) {
-            t = t + 1;
-            // 同一円周上となる4点が存在するかをチェック
-            if !has_any_4_concyclic(&subset.iter().map(|&&t| t).collect::<Vec<(i32, i32)>>()) {
-                // もし同一円周上4点が存在しなければOK
-                found_good_subset = true;
-                println!(
-                    "同一円周上となる4点を含まない部分集合が存在する n = {},{:#?}",
-                    n, subset
-                );
-                break;
+
+    let best = Mutex::new(best_indices);
+    let completed = Mutex::new(checkpoint.completed_starts);
+    let total_nodes = AtomicU64::new(0);
+    let total_starts = all_points.len();
+    let started = Instant::now();
+
+    (0..total_starts).into_par_iter().for_each(|start| {
+        if completed.lock().unwrap().contains(&start) {
+            return;
+        }
+
+        let (local_best, node_count) =
+            search_max_no_4_concyclic_from_start(start, all_points, point_circles, circle_count);
+        total_nodes.fetch_add(node_count, Ordering::Relaxed);
+
+        let mut best_guard = best.lock().unwrap();
+        if local_best.len() > best_guard.len() {
+            *best_guard = local_best;
+        }
+        let mut completed_guard = completed.lock().unwrap();
+        completed_guard.insert(start);
+        let done = completed_guard.len();
+        let best_points: Vec<Point> = best_guard.iter().map(|&i| all_points[i]).collect();
+        save_checkpoint(checkpoint_path, fingerprint, &completed_guard, &best_points);
+        drop(completed_guard);
+        drop(best_guard);
+
+        println!(
+            "進捗: {}/{} 分割完了, ノード数(累計) = {}, 経過時間 = {:.1}秒",
+            done,
+            total_starts,
+            total_nodes.load(Ordering::Relaxed),
+            started.elapsed().as_secs_f64()
+        );
+    });
+
+    let final_best = best.into_inner().unwrap();
+    (final_best, total_nodes.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod checkpointed_search_tests {
+    use super::*;
+
+    static TEST_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    /// テストごとに衝突しない一時チェックポイントパスを作る
+    fn unique_checkpoint_path() -> std::path::PathBuf {
+        let n = TEST_FILE_COUNTER.fetch_add(1, Ordering::SeqCst);
+        std::env::temp_dir().join(format!("kyoen_checkpoint_test_{}_{}.txt", std::process::id(), n))
+    }
+
+    #[test]
+    fn parallel_search_matches_sequential_on_3x3_grid() {
+        let all_points: Vec<Point> = (0..3).flat_map(|x| (0..3).map(move |y| (x, y))).collect();
+        let circles = build_circle_groups(&all_points);
+        let point_circles = point_to_circles(all_points.len(), &circles);
+        let path = unique_checkpoint_path();
+
+        let (best, _total_nodes) = search_max_no_4_concyclic_parallel(
+            &all_points,
+            &point_circles,
+            circles.len(),
+            path.to_str().unwrap(),
+        );
+        let _ = std::fs::remove_file(&path);
+
+        let best_points: Vec<Point> = best.iter().map(|&i| all_points[i]).collect();
+        assert!(!has_any_4_concyclic(&best_points));
+
+        let mut counts = vec![0u8; circles.len()];
+        let mut chosen = Vec::new();
+        let mut sequential_best = Vec::new();
+        let mut node_count = 0u64;
+        search_max_no_4_concyclic(
+            0,
+            &all_points,
+            &point_circles,
+            &mut counts,
+            &mut chosen,
+            &mut sequential_best,
+            &mut node_count,
+        );
+        assert_eq!(best.len(), sequential_best.len());
+    }
+
+    #[test]
+    fn checkpoint_round_trips_through_save_and_load() {
+        let all_points: Vec<Point> = vec![(0, 0), (1, 0), (0, 1), (1, 1)];
+        let fingerprint = fingerprint_points(&all_points);
+        let path = unique_checkpoint_path();
+        let path_str = path.to_str().unwrap();
+
+        let mut completed_starts = std::collections::HashSet::new();
+        completed_starts.insert(0);
+        completed_starts.insert(2);
+        let best = vec![(0, 0), (1, 1)];
+        save_checkpoint(path_str, fingerprint, &completed_starts, &best);
+
+        let loaded = load_checkpoint(path_str, fingerprint);
+        assert_eq!(loaded.completed_starts, completed_starts);
+        assert_eq!(loaded.best, best);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn checkpoint_with_mismatched_fingerprint_is_discarded() {
+        let all_points: Vec<Point> = vec![(0, 0), (1, 0), (0, 1), (1, 1)];
+        let fingerprint = fingerprint_points(&all_points);
+        let other_fingerprint = fingerprint.wrapping_add(1);
+        let path = unique_checkpoint_path();
+        let path_str = path.to_str().unwrap();
+
+        let mut completed_starts = std::collections::HashSet::new();
+        completed_starts.insert(0);
+        let best = vec![(0, 0), (1, 1)];
+        save_checkpoint(path_str, fingerprint, &completed_starts, &best);
+
+        // 別の点集合の指紋で読むと、中身を信用せず空のチェックポイントになる
+        let loaded = load_checkpoint(path_str, other_fingerprint);
+        assert!(loaded.completed_starts.is_empty());
+        assert!(loaded.best.is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
+
+/// 点 `p` が三角形 `a, b, c` の内部に厳密に含まれるかどうか。
+///
+/// `p` を三角形の3辺それぞれについて `area2` で判定し、符号がすべて同じ
+/// (すべて正またはすべて負)なら内部にある。3点の組が共線でない限り、
+/// また `p` がどの辺とも共線でない限り `area2` が 0 になることはない。
+fn point_strictly_inside_triangle(p: &Point, a: &Point, b: &Point, c: &Point) -> bool {
+    let d1 = area2(a, b, p);
+    let d2 = area2(b, c, p);
+    let d3 = area2(c, a, p);
+    (d1 > 0 && d2 > 0 && d3 > 0) || (d1 < 0 && d2 < 0 && d3 < 0)
+}
+
+/// 4点が凸位置にあるかどうか。
+///
+/// 4点のうち3点が共線でないことを前提とする(そうでなければ三角形が
+/// 退化し、内外判定ができない)。各点について、残り3点が作る三角形の
+/// 内部に厳密に含まれるかどうかを調べ、4点すべてが外部にあれば凸位置。
+/// 1点でも他の3点の三角形の内部にあれば、その4点は凸位置ではない
+/// (非凸=1点が残り3点の凸包の内側)。
+fn four_points_in_convex_position(p1: &Point, p2: &Point, p3: &Point, p4: &Point) -> bool {
+    let pts = [p1, p2, p3, p4];
+    for i in 0..4 {
+        let others: Vec<&Point> = (0..4).filter(|&j| j != i).map(|j| pts[j]).collect();
+        if point_strictly_inside_triangle(pts[i], others[0], others[1], others[2]) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod convex_position_tests {
+    use super::*;
+
+    #[test]
+    fn square_corners_are_convex() {
+        assert!(four_points_in_convex_position(
+            &(0, 0),
+            &(2, 0),
+            &(2, 2),
+            &(0, 2)
+        ));
+    }
+
+    #[test]
+    fn point_inside_triangle_of_others_is_not_convex() {
+        assert!(!four_points_in_convex_position(
+            &(0, 0),
+            &(4, 0),
+            &(0, 4),
+            &(1, 1)
+        ));
+    }
+
+    #[test]
+    fn has_any_4_convex_is_false_for_four_points_with_one_inside_the_triangle() {
+        // Happy Ending 問題により、一般の位置の4点までは凸四角形を
+        // 含まない構成があり得る(5点目を加えると必ず含む)
+        let no_quad = [(0, 0), (4, 0), (1, 1), (2, 5)];
+        assert!(!has_any_4_convex(&no_quad));
+    }
+}
+
+/// 点 `point` が単純多角形 `polygon` (頂点を反時計回り/時計回りいずれかの順で
+/// 並べたもの)の内部にあるかどうかを、巻き数(winding number)法で判定する。
+///
+/// 多角形の頂点を `point` が原点になるよう平行移動し、連続する頂点の組
+/// `(x1,y1) -> (x2,y2)` ごとに、辺が半開区間 `[y1, y2)` の意味でx軸を
+/// 上向き/下向きに横切るかどうかを調べる(Dan Sunday の winding number
+/// アルゴリズム)。半開区間を使うことで、頂点がちょうどx軸上に乗る場合も
+/// 特別扱いせずに厳密な整数演算だけで正しく処理できる。
+/// 辺が横切る向きは外積 `x1*y2 - x2*y1` の符号で判定し、上向きの横切りで
+/// +1、下向きの横切りで -1 する。最終的な巻き数が 0 でなければ内部。
+fn is_inside_polygon(point: &Point, polygon: &[Point]) -> bool {
+    let n = polygon.len();
+    let mut winding: i64 = 0;
+    for i in 0..n {
+        let (vx1, vy1) = polygon[i];
+        let (vx2, vy2) = polygon[(i + 1) % n];
+        let x1 = (vx1 - point.0) as i64;
+        let y1 = (vy1 - point.1) as i64;
+        let x2 = (vx2 - point.0) as i64;
+        let y2 = (vy2 - point.1) as i64;
+        let cross = x1 * y2 - x2 * y1;
+
+        if y1 <= 0 {
+            // 上向きの横切り: y1 <= 0 < y2 かつ交点がx軸の正の側にある
+            if y2 > 0 && cross > 0 {
+                winding += 1;
             }
-            if (t % 10000000) == 0 {
-                println!("{}:{} 千万回目",  Local::now(),t / 10000000); // 合計25億
+        } else {
+            // 下向きの横切り: y2 <= 0 < y1 かつ交点がx軸の正の側にある
+            if y2 <= 0 && cross < 0 {
+                winding -= 1;
             }
         }
-        if !found_good_subset {
-            println!("{} 存在しない", n);
-            break;
+    }
+    winding != 0
+}
+
+/// グリッド上の点のうち、単純多角形 `polygon` の内部にあるものだけを残す。
+/// 三角形・L字型・円に近い多角形など、正方形以外の領域に探索を絞り込むために使う。
+pub fn filter_points_in_polygon(points: &[Point], polygon: &[Point]) -> Vec<Point> {
+    points
+        .iter()
+        .filter(|p| is_inside_polygon(p, polygon))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod polygon_tests {
+    use super::*;
+
+    const SQUARE: [Point; 4] = [(0, 0), (4, 0), (4, 4), (0, 4)];
+    const L_SHAPE: [Point; 6] = [(0, 0), (4, 0), (4, 2), (2, 2), (2, 4), (0, 4)];
+    const TRIANGLE: [Point; 3] = [(0, 0), (5, 0), (0, 5)];
+
+    #[test]
+    fn square_excludes_points_to_the_left_of_it() {
+        for p in [(-2, 0), (-1, 0), (-2, 2), (-2, 4), (-1, 4)] {
+            assert!(!is_inside_polygon(&p, &SQUARE), "{:?} should be outside", p);
         }
     }
 
-    // もし何も見つからなければ n=0? (通常はあり得ないが念のため)
-    println!("条件を満たす部分集合は見つかりませんでした。");
+    #[test]
+    fn square_includes_interior_and_excludes_far_corner() {
+        assert!(is_inside_polygon(&(2, 2), &SQUARE));
+        assert!(is_inside_polygon(&(1, 1), &SQUARE));
+        assert!(!is_inside_polygon(&(6, 6), &SQUARE));
+    }
+
+    #[test]
+    fn l_shape_excludes_points_to_the_left_at_every_row() {
+        for y in [0, 2, 4] {
+            for x in [-2, -1] {
+                assert!(
+                    !is_inside_polygon(&(x, y), &L_SHAPE),
+                    "({}, {}) should be outside",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn l_shape_includes_points_in_both_arms_and_excludes_the_missing_corner() {
+        assert!(is_inside_polygon(&(1, 1), &L_SHAPE));
+        assert!(is_inside_polygon(&(3, 1), &L_SHAPE));
+        assert!(!is_inside_polygon(&(3, 3), &L_SHAPE));
+    }
+
+    #[test]
+    fn triangle_excludes_points_above_the_hypotenuse_and_to_the_left() {
+        for p in [(-2, 5), (-1, 5)] {
+            assert!(!is_inside_polygon(&p, &TRIANGLE), "{:?} should be outside", p);
+        }
+        assert!(is_inside_polygon(&(1, 1), &TRIANGLE));
+        assert!(!is_inside_polygon(&(10, 10), &TRIANGLE));
+    }
+}
+
+/// 部分集合 subset (3点共線を含まない一般の位置)の中に、
+/// 凸位置となる4点の組合せが一つでもあれば true
+fn has_any_4_convex(subset: &[Point]) -> bool {
+    for comb4 in subset.iter().combinations(4) {
+        let p1 = comb4[0];
+        let p2 = comb4[1];
+        let p3 = comb4[2];
+        let p4 = comb4[3];
+        if four_points_in_convex_position(p1, p2, p3, p4) {
+            return true;
+        }
+    }
+    false
+}
+
+/// 「3点共線なし」かつ「凸位置の4点なし」という不変条件を保ちながら
+/// グリッドの点を深さ優先で選んでいく branch-and-bound 探索(Happy Ending 版)。
+///
+/// Erdős–Szekeres の定理(Happy Ending 問題)により、一般の位置にある5点は
+/// 必ず凸位置の4点を含むため、この探索で得られる最大サイズは高々4になる。
+fn search_max_no_convex_quad(
+    idx: usize,
+    all_points: &[Point],
+    chosen: &mut Vec<usize>,
+    best: &mut Vec<usize>,
+) {
+    if chosen.len() + (all_points.len() - idx) <= best.len() {
+        return;
+    }
+    if idx == all_points.len() {
+        return;
+    }
+
+    let p = &all_points[idx];
+    let mut ok = chosen
+        .iter()
+        .combinations(2)
+        .all(|pair| area2(&all_points[*pair[0]], &all_points[*pair[1]], p) != 0);
+    if ok {
+        ok = chosen.iter().combinations(3).all(|triple| {
+            !four_points_in_convex_position(
+                &all_points[*triple[0]],
+                &all_points[*triple[1]],
+                &all_points[*triple[2]],
+                p,
+            )
+        });
+    }
+
+    if ok {
+        chosen.push(idx);
+        if chosen.len() > best.len() {
+            *best = chosen.clone();
+        }
+        search_max_no_convex_quad(idx + 1, all_points, chosen, best);
+        chosen.pop();
+    }
+
+    search_max_no_convex_quad(idx + 1, all_points, chosen, best);
+}
+
+fn main() {
+    let hen = 6;
+    let grid_points: Vec<Point> = (0..=hen)
+        .flat_map(|x| (0..=hen).map(move |y| (x, y)))
+        .collect();
+    println!("大きさは{}x{}", hen + 1, hen + 1);
+
+    // 探索対象の領域を単純多角形として指定する。ここではグリッド全体を
+    // すっぽり覆う正方形(境界上の点の内外判定のあいまいさを避けるため、
+    // グリッドよりひと回り大きく取る)を与えているが、三角形やL字型の
+    // 頂点列に差し替えれば、そのまま対応する領域内の点だけに絞り込める。
+    let region_polygon: Vec<Point> = vec![
+        (-1, -1),
+        (hen + 1, -1),
+        (hen + 1, hen + 1),
+        (-1, hen + 1),
+    ];
+    let all_points: Vec<Point> = filter_points_in_polygon(&grid_points, &region_polygon);
+    println!(
+        "点の総数: {} (多角形領域で絞り込む前は {})",
+        all_points.len(),
+        grid_points.len()
+    );
+
+    print_constraint_groups(&all_points);
+
+    let circles = build_circle_groups(&all_points);
+    println!("4点以上が乗る円の数: {}", circles.len());
+    let point_circles = point_to_circles(all_points.len(), &circles);
+
+    println!(
+        "並列探索を開始します(スレッド数 = {}, チェックポイント = {})",
+        rayon::current_num_threads(),
+        checkpoint_path()
+    );
+    let started = Instant::now();
+    let (best, total_nodes) = search_max_no_4_concyclic_parallel(
+        &all_points,
+        &point_circles,
+        circles.len(),
+        checkpoint_path(),
+    );
+    let elapsed = started.elapsed().as_secs_f64();
+    println!(
+        "探索ノード数(全スレッド合計) = {}, 経過時間 = {:.3}秒, スループット = {:.0} ノード/秒",
+        total_nodes,
+        elapsed,
+        if elapsed > 0.0 {
+            total_nodes as f64 / elapsed
+        } else {
+            total_nodes as f64
+        }
+    );
+
+    let best_points: Vec<Point> = best.iter().map(|&i| all_points[i]).collect();
+    debug_assert!(!has_any_4_concyclic(&best_points));
+
+    println!(
+        "同一円周上4点を含まない最大の部分集合: サイズ = {}",
+        best_points.len()
+    );
+    println!("{:#?}", best_points);
+
+    // Happy Ending 問題: 凸位置の4点を含まない最大の部分集合を探す
+    let mut happy_chosen = Vec::new();
+    let mut happy_best = Vec::new();
+    search_max_no_convex_quad(0, &all_points, &mut happy_chosen, &mut happy_best);
+
+    let happy_best_points: Vec<Point> = happy_best.iter().map(|&i| all_points[i]).collect();
+    debug_assert!(!has_any_4_convex(&happy_best_points));
+    // Erdős–Szekeres の定理より、一般の位置の5点は必ず凸四角形を含むので
+    // このサイズは高々4になるはず(ビルトインの健全性チェック)
+    assert!(
+        happy_best_points.len() <= 4,
+        "一般の位置の5点以上は必ず凸四角形を含むはずだが、サイズ{}の反例が見つかった",
+        happy_best_points.len()
+    );
+
+    println!(
+        "凸位置の4点を含まない最大の部分集合(Happy Ending): サイズ = {}",
+        happy_best_points.len()
+    );
+    println!("{:#?}", happy_best_points);
 }